@@ -1,4 +1,4 @@
-use std::{fmt::Debug, fmt::Display, ops::Range, str::FromStr};
+use std::{cmp::Ordering, fmt::Debug, fmt::Display, ops::Range, str::FromStr};
 
 use bytes::{Bytes, BytesMut};
 use ethereum_types::{H256, U256};
@@ -15,6 +15,31 @@ use crate::{
 #[error(transparent)]
 pub struct StrToNibblesError(#[from] FromHexError);
 
+#[derive(Error, Debug)]
+/// An error returned when a byte slice is not a valid hex-prefix (compact)
+/// encoding of `Nibbles`.
+pub enum FromHexPrefixEncodingError {
+    #[error("Cannot decode `Nibbles` from an empty hex-prefix encoded byte slice")]
+    EmptyBytes,
+
+    #[error(
+        "Expected the low nibble of the first byte to be `0` when the odd-length flag is not set, but got `{0:#x}`"
+    )]
+    NonZeroLowNibbleOnEvenLength(u8),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "Merging a `Nibbles` with {self_count} nibbles and one with {post_count} nibbles would exceed the 64-nibble capacity of `Nibbles`"
+)]
+/// Returned by [`Nibbles::try_merge`] when the combined length of the two
+/// `Nibbles` would exceed the 64-nibble capacity of the packed `U256`
+/// representation.
+pub struct NibblesMergeOverflowError {
+    pub self_count: usize,
+    pub post_count: usize,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 /// A partial trie, or a sub-trie thereof. This mimics the structure of an
 /// Ethereum trie, except with an additional `Hash` node type, representing a
@@ -88,7 +113,7 @@ impl Default for PartialTrie {
     }
 }
 
-#[derive(Copy, Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[derive(Copy, Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
 /// A sequence of nibbles.
 pub struct Nibbles {
     /// The number of nibbles in this sequence.
@@ -98,6 +123,33 @@ pub struct Nibbles {
     pub packed: U256,
 }
 
+// Manual impl so that ordering is lexicographic by nibble sequence (matching
+// parity's `NibbleSlice`) rather than by `count` first. `0x12` must sort
+// before `0x9`, even though `0x12` has more nibbles.
+impl PartialOrd for Nibbles {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nibbles {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let min_count = self.count.min(other.count);
+
+        for i in 0..min_count {
+            let ord = self.get_nibble(i).cmp(&other.get_nibble(i));
+
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+
+        // One is a prefix of the other (or they're equal); the shorter is
+        // "less".
+        self.count.cmp(&other.count)
+    }
+}
+
 impl Display for Nibbles {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_hex_str())
@@ -219,6 +271,13 @@ impl Nibbles {
     }
 
     pub fn truncate_n_nibbles_mut(&mut self, n: usize) {
+        debug_assert!(
+            n <= self.count,
+            "Tried truncating {} nibbles from a `Nibbles` with only {} nibbles!",
+            n,
+            self.count
+        );
+
         let mask_shift = (self.count - n) * 4;
         let truncate_mask = !(create_mask_of_1s(n * 4) << mask_shift);
 
@@ -226,9 +285,32 @@ impl Nibbles {
         self.packed = self.packed & truncate_mask;
     }
 
+    /// Returns the suffix of `self` starting at nibble `n`, mirroring parity's
+    /// `NibbleSlice::mid`. Equivalent to [`Nibbles::truncate_n_nibbles`], but
+    /// named to match traversal intent.
+    pub fn mid(&self, n: usize) -> Nibbles {
+        self.truncate_n_nibbles(n)
+    }
+
+    /// Returns an iterator over the individual nibbles, most significant
+    /// first (matching `get_nibble(0)`).
+    pub fn iter(&self) -> NibblesIter {
+        NibblesIter {
+            nibbles: *self,
+            idx: 0,
+        }
+    }
+
     /// Splits the `Nibbles` at the given index, returning two `Nibbles`.
     /// Specifically, if `0x1234` is split at `1`, we get `0x1` and `0x234`.
     pub fn split_at_idx(&self, idx: usize) -> (Nibbles, Nibbles) {
+        debug_assert!(
+            idx <= self.count,
+            "Tried splitting a `Nibbles` with {} nibbles at index {}!",
+            self.count,
+            idx
+        );
+
         let post_count = self.count - idx;
         let post_mask = create_mask_of_1s(post_count * 4);
 
@@ -248,6 +330,13 @@ impl Nibbles {
     }
 
     pub fn split_at_idx_prefix(&self, idx: usize) -> Nibbles {
+        debug_assert!(
+            idx <= self.count,
+            "Tried splitting a `Nibbles` with {} nibbles at index {}!",
+            self.count,
+            idx
+        );
+
         let shift_amt = (self.count - idx) * 4;
         let pre_mask = create_mask_of_1s(idx * 4) << shift_amt;
 
@@ -258,6 +347,13 @@ impl Nibbles {
     }
 
     pub fn split_at_idx_postfix(&self, idx: usize) -> Nibbles {
+        debug_assert!(
+            idx <= self.count,
+            "Tried splitting a `Nibbles` with {} nibbles at index {}!",
+            self.count,
+            idx
+        );
+
         let postfix_count = self.count - idx;
         let mask = create_mask_of_1s(postfix_count * 4);
 
@@ -268,13 +364,44 @@ impl Nibbles {
     }
 
     /// Merge two `Nibbles` together. `self` will be the prefix.
+    ///
+    /// Debug-asserts that the combined length fits in the 64-nibble capacity
+    /// of the packed `U256` representation; use [`Nibbles::try_merge`] if the
+    /// combined length isn't already known to be in range (e.g. when
+    /// concatenating extension paths during trie restructuring).
     pub fn merge(&self, post: &Nibbles) -> Nibbles {
+        debug_assert!(
+            self.count + post.count <= 64,
+            "Merging these `Nibbles` would exceed the 64-nibble capacity of `Nibbles`: {} + {} > 64",
+            self.count,
+            post.count
+        );
+
         Nibbles {
             count: self.count + post.count,
             packed: (self.packed << (post.count * 4)) | post.packed,
         }
     }
 
+    /// Fallible version of [`Nibbles::merge`] that returns an error instead
+    /// of silently truncating (in release builds) or panicking (in debug
+    /// builds) when the combined length would exceed the 64-nibble capacity
+    /// of the packed representation.
+    // TODO: Consider an opt-in arbitrary-length backing (e.g. a `count` +
+    // `Vec<u8>` representation, or a `U512`-packed variant) so that
+    // intermediate concatenations during trie restructuring aren't bound by
+    // the 64-nibble capacity of `U256`.
+    pub fn try_merge(&self, post: &Nibbles) -> Result<Nibbles, NibblesMergeOverflowError> {
+        if self.count + post.count > 64 {
+            return Err(NibblesMergeOverflowError {
+                self_count: self.count,
+                post_count: post.count,
+            });
+        }
+
+        Ok(self.merge(post))
+    }
+
     /// Finds the nibble idx that differs between two nibbles.
     /// If there is no difference, returns 1 + the last index.
     pub fn find_nibble_idx_that_differs_between_nibbles(n1: &Nibbles, n2: &Nibbles) -> usize {
@@ -296,6 +423,27 @@ impl Nibbles {
         n1.count
     }
 
+    /// Finds the length of the common prefix between `self` and `other`,
+    /// tolerating differing `count`s (unlike
+    /// [`Nibbles::find_nibble_idx_that_differs_between_nibbles`]). Mirrors
+    /// the `common_prefix` method on parity's `NibbleSlice`.
+    pub fn common_prefix_len(&self, other: &Nibbles) -> usize {
+        let min_count = self.count.min(other.count);
+
+        for i in 0..min_count {
+            if self.get_nibble(i) != other.get_nibble(i) {
+                return i;
+            }
+        }
+
+        min_count
+    }
+
+    /// Returns `true` if `self` starts with all of `prefix`'s nibbles.
+    pub fn starts_with(&self, prefix: &Nibbles) -> bool {
+        self.common_prefix_len(prefix) == prefix.count
+    }
+
     pub fn get_num_nibbles_in_key(k: &U256) -> usize {
         (k.bits() + 3) / 4
     }
@@ -355,6 +503,38 @@ impl Nibbles {
         Bytes::copy_from_slice(&bytes[flag_byte_idx..33])
     }
 
+    /// Parses `Nibbles` from a hex-prefix (compact) encoded byte slice, the
+    /// inverse of [`Nibbles::to_hex_prefix_encoding`]. Returns the decoded
+    /// nibbles along with the leaf (terminator) flag.
+    pub fn from_hex_prefix_encoding(bytes: &[u8]) -> Result<(Self, bool), FromHexPrefixEncodingError> {
+        let first_byte = *bytes
+            .first()
+            .ok_or(FromHexPrefixEncodingError::EmptyBytes)?;
+
+        let is_odd = (first_byte & 0b0001_0000) != 0;
+        let is_leaf = (first_byte & 0b0010_0000) != 0;
+
+        let mut packed = U256::zero();
+        let mut count = 0;
+
+        if is_odd {
+            packed = U256::from(first_byte & 0b0000_1111);
+            count += 1;
+        } else if first_byte & 0b0000_1111 != 0 {
+            return Err(FromHexPrefixEncodingError::NonZeroLowNibbleOnEvenLength(
+                first_byte & 0b0000_1111,
+            ));
+        }
+
+        for &byte in &bytes[1..] {
+            packed = (packed << 4) | U256::from(byte >> 4);
+            packed = (packed << 4) | U256::from(byte & 0b0000_1111);
+            count += 2;
+        }
+
+        Ok((Self { count, packed }, is_leaf))
+    }
+
     /// Returns the minimum number of bytes needed to represent these `Nibbles`.
     pub fn min_bytes(&self) -> usize {
         (self.count + 1) / 2
@@ -369,6 +549,28 @@ impl Nibbles {
     }
 }
 
+/// An iterator over the individual nibbles of a [`Nibbles`], most significant
+/// first. Created by [`Nibbles::iter`].
+pub struct NibblesIter {
+    nibbles: Nibbles,
+    idx: usize,
+}
+
+impl Iterator for NibblesIter {
+    type Item = Nibble;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx >= self.nibbles.count {
+            return None;
+        }
+
+        let nib = self.nibbles.get_nibble(self.idx);
+        self.idx += 1;
+
+        Some(nib)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ethereum_types::U256;
@@ -535,6 +737,119 @@ mod tests {
         assert_eq!(nib.packed, U256::from(0x12));
     }
 
+    #[test]
+    fn try_merge_succeeds_within_capacity() {
+        assert_eq!(
+            nibbles(0x12).try_merge(&nibbles(0x34)).unwrap(),
+            nibbles(0x1234)
+        );
+    }
+
+    #[test]
+    fn try_merge_rejects_combined_length_over_64_nibbles() {
+        let sixty_four_nibbles = Nibbles {
+            count: 64,
+            packed: U256::zero(),
+        };
+        let one_more_nibble = nibbles(0x1);
+
+        let err = sixty_four_nibbles.try_merge(&one_more_nibble).unwrap_err();
+
+        assert_eq!(err.self_count, 64);
+        assert_eq!(err.post_count, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_debug_asserts_when_combined_length_exceeds_64_nibbles() {
+        let sixty_four_nibbles = Nibbles {
+            count: 64,
+            packed: U256::zero(),
+        };
+
+        sixty_four_nibbles.merge(&nibbles(0x1));
+    }
+
+    #[test]
+    fn iter_yields_nibbles_most_significant_first() {
+        let n = nibbles(0x1234);
+        let collected: Vec<_> = n.iter().collect();
+
+        assert_eq!(collected, vec![0x1, 0x2, 0x3, 0x4]);
+    }
+
+    #[test]
+    fn iter_on_empty_nibbles_yields_nothing() {
+        assert_eq!(nibbles(0x0).iter().next(), None);
+    }
+
+    #[test]
+    fn mid_is_equivalent_to_truncate_n_nibbles() {
+        let n = nibbles(0x1234);
+
+        assert_eq!(n.mid(0), n);
+        assert_eq!(n.mid(1), nibbles(0x234));
+        assert_eq!(n.mid(4), nibbles(0x0));
+    }
+
+    #[test]
+    fn common_prefix_len_tolerates_unequal_length() {
+        assert_eq!(nibbles(0x1234).common_prefix_len(&nibbles(0x1234)), 4);
+        assert_eq!(nibbles(0x1234).common_prefix_len(&nibbles(0x12)), 2);
+        assert_eq!(nibbles(0x12).common_prefix_len(&nibbles(0x1234)), 2);
+        assert_eq!(nibbles(0x1234).common_prefix_len(&nibbles(0x1256)), 2);
+        assert_eq!(nibbles(0x1234).common_prefix_len(&nibbles(0x9)), 0);
+    }
+
+    #[test]
+    fn starts_with_works() {
+        assert!(nibbles(0x1234).starts_with(&nibbles(0x12)));
+        assert!(nibbles(0x1234).starts_with(&nibbles(0x0)));
+        assert!(nibbles(0x1234).starts_with(&nibbles(0x1234)));
+
+        assert!(!nibbles(0x1234).starts_with(&nibbles(0x13)));
+        assert!(!nibbles(0x12).starts_with(&nibbles(0x1234)));
+    }
+
+    #[test]
+    fn hex_prefix_encoding_round_trips_through_decoding() {
+        assert_hex_prefix_round_trip(nibbles(0x1234), false);
+        assert_hex_prefix_round_trip(nibbles(0x1234), true);
+        assert_hex_prefix_round_trip(nibbles(0x12345), false);
+        assert_hex_prefix_round_trip(nibbles(0x12345), true);
+        assert_hex_prefix_round_trip(nibbles(0x0), false);
+        assert_hex_prefix_round_trip(nibbles(0x0), true);
+    }
+
+    fn assert_hex_prefix_round_trip(n: Nibbles, is_leaf: bool) {
+        let encoded = n.to_hex_prefix_encoding(is_leaf);
+        let (decoded, decoded_is_leaf) = Nibbles::from_hex_prefix_encoding(&encoded).unwrap();
+
+        assert_eq!(decoded, n);
+        assert_eq!(decoded_is_leaf, is_leaf);
+    }
+
+    #[test]
+    fn from_hex_prefix_encoding_rejects_empty_bytes() {
+        assert!(Nibbles::from_hex_prefix_encoding(&[]).is_err());
+    }
+
+    #[test]
+    fn ordering_is_lexicographic_by_nibble_not_count() {
+        // `0x12` (count 2) should sort before `0x9` (count 1), since the
+        // first nibble `0x1` is less than `0x9`.
+        assert!(nibbles(0x12) < nibbles(0x9));
+        assert!(nibbles(0x9) > nibbles(0x12));
+    }
+
+    #[test]
+    fn ordering_treats_shorter_prefix_as_less() {
+        // `0x12` is a strict prefix of `0x123`, so it should sort first.
+        assert!(nibbles(0x12) < nibbles(0x123));
+        assert!(nibbles(0x123) > nibbles(0x12));
+        assert_eq!(nibbles(0x12).cmp(&nibbles(0x12)), std::cmp::Ordering::Equal);
+    }
+
     #[test]
     fn nibbles_to_hex_prefix_encoding_works() {
         assert_eq!(to_hex_prefix_encoding(0x1234, false), 0x1234);
@@ -0,0 +1,139 @@
+//! Computing the Ethereum Merkle Patricia Trie root hash of a `PartialTrie`.
+
+use ethereum_types::H256;
+use keccak_hash::keccak;
+use rlp::RlpStream;
+
+use crate::partial_trie::PartialTrie;
+
+/// The RLP encoding of the empty string, used as the encoding of `Empty`
+/// nodes.
+const RLP_EMPTY_STRING: [u8; 1] = [0x80];
+
+impl PartialTrie {
+    /// Computes the Ethereum Merkle Patricia Trie root hash of this trie.
+    pub fn hash(&self) -> H256 {
+        match self {
+            // The hash of a pruned subtree is just the digest we already have.
+            PartialTrie::Hash(h) => *h,
+            _ => keccak(self.rlp_encode()),
+        }
+    }
+
+    /// RLP-encodes this node, following the standard Ethereum MPT node
+    /// encoding.
+    fn rlp_encode(&self) -> Vec<u8> {
+        match self {
+            PartialTrie::Empty => RLP_EMPTY_STRING.to_vec(),
+            PartialTrie::Hash(h) => rlp::encode(&h.as_bytes()).to_vec(),
+            PartialTrie::Leaf { nibbles, value } => {
+                let encoded_path = nibbles.to_hex_prefix_encoding(true);
+                let mut stream = RlpStream::new_list(2);
+                stream.append(&encoded_path);
+                stream.append(value);
+                stream.out().to_vec()
+            }
+            PartialTrie::Extension { nibbles, child } => {
+                let encoded_path = nibbles.to_hex_prefix_encoding(false);
+                let mut stream = RlpStream::new_list(2);
+                stream.append(&encoded_path);
+                stream.append_raw(&child.rlp_encode_child_ref(), 1);
+                stream.out().to_vec()
+            }
+            PartialTrie::Branch { children, value } => {
+                let mut stream = RlpStream::new_list(17);
+                for child in children {
+                    stream.append_raw(&child.rlp_encode_child_ref(), 1);
+                }
+                stream.append(value);
+                stream.out().to_vec()
+            }
+        }
+    }
+
+    /// Computes the child reference used when this node is embedded inside
+    /// its parent's RLP encoding: the node's own encoding if it is shorter
+    /// than 32 bytes, or the `keccak256` digest of that encoding otherwise.
+    fn rlp_encode_child_ref(&self) -> Vec<u8> {
+        if let PartialTrie::Hash(h) = self {
+            return rlp::encode(&h.as_bytes()).to_vec();
+        }
+
+        let encoding = self.rlp_encode();
+
+        if encoding.len() < 32 {
+            encoding
+        } else {
+            rlp::encode(&keccak(&encoding).as_bytes()).to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::H256;
+    use std::str::FromStr;
+
+    use crate::partial_trie::PartialTrie;
+    use crate::utils::nibbles;
+
+    #[test]
+    fn empty_trie_hash_matches_known_ethereum_empty_root() {
+        let empty_root =
+            H256::from_str("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+                .unwrap();
+
+        assert_eq!(PartialTrie::Empty.hash(), empty_root);
+    }
+
+    #[test]
+    fn single_leaf_trie_hash_matches_known_root() {
+        let trie = PartialTrie::Leaf {
+            nibbles: nibbles(0x12),
+            value: b"hello".to_vec(),
+        };
+
+        let expected =
+            H256::from_str("eccdc337ef7fbc2a64e9a55aba8b5c86619ea4f2e1970391cf539b4bc909c536")
+                .unwrap();
+
+        assert_eq!(trie.hash(), expected);
+    }
+
+    #[test]
+    fn hash_node_returns_its_stored_digest() {
+        let h = H256::random();
+        assert_eq!(PartialTrie::Hash(h).hash(), h);
+    }
+
+    #[test]
+    fn two_leaves_sharing_a_prefix_hash_matches_known_root() {
+        // An `Extension` over a `Branch` with two `Leaf` children, which
+        // exercises the child-reference embedding for both node kinds.
+        let mut children: [Box<PartialTrie>; 16] = Default::default();
+        children[3] = Box::new(PartialTrie::Leaf {
+            nibbles: nibbles(0x4),
+            value: b"hello".to_vec(),
+        });
+        children[5] = Box::new(PartialTrie::Leaf {
+            nibbles: nibbles(0x6),
+            value: b"world".to_vec(),
+        });
+
+        let branch = PartialTrie::Branch {
+            children,
+            value: Vec::new(),
+        };
+
+        let trie = PartialTrie::Extension {
+            nibbles: nibbles(0x12),
+            child: Box::new(branch),
+        };
+
+        let expected =
+            H256::from_str("d96469a1a7561ac10456a674a77c249b61309d1237108fe128cbef804fbaf472")
+                .unwrap();
+
+        assert_eq!(trie.hash(), expected);
+    }
+}
@@ -0,0 +1,317 @@
+//! Key-path lookup and insertion over a `PartialTrie`.
+
+use std::mem;
+
+use thiserror::Error;
+
+use crate::partial_trie::{Nibbles, PartialTrie};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+/// An error produced while looking up a key in a `PartialTrie`.
+pub enum TrieGetError {
+    /// The traversal reached a `Hash` node (whether or not any of the key
+    /// remains), meaning the relevant subtree's data has been pruned and
+    /// cannot be searched.
+    #[error(
+        "Hit a `Hash` node with `{0}` nibbles of the key still remaining; the subtree's data has been pruned"
+    )]
+    HitHashNode(Nibbles),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+/// An error produced while inserting into a `PartialTrie`.
+pub enum TrieInsertError {
+    /// The insertion path descended into a `Hash` node (whether or not any
+    /// of the key remains), meaning the relevant subtree's data has been
+    /// pruned and there's nothing to merge the new value into.
+    #[error(
+        "Hit a `Hash` node with `{0}` nibbles of the key still remaining; the subtree's data has been pruned and cannot be inserted into"
+    )]
+    HitHashNode(Nibbles),
+}
+
+/// What an `Extension`/`Leaf` node was carrying before it got split by
+/// [`split_diverging_node`].
+enum ExistingTail {
+    ExtensionChild(Box<PartialTrie>),
+    LeafValue(Vec<u8>),
+}
+
+impl PartialTrie {
+    /// Looks up `key` in this trie, returning the associated value if
+    /// present.
+    pub fn get(&self, key: Nibbles) -> Result<Option<&[u8]>, TrieGetError> {
+        match self {
+            PartialTrie::Empty => Ok(None),
+            PartialTrie::Hash(_) => Err(TrieGetError::HitHashNode(key)),
+            PartialTrie::Branch { children, value } => match key.count {
+                0 => Ok(value_if_present(value)),
+                _ => children[key.get_nibble(0) as usize].get(key.mid(1)),
+            },
+            PartialTrie::Extension { nibbles, child } => match key.starts_with(nibbles) {
+                true => child.get(key.mid(nibbles.count)),
+                false => Ok(None),
+            },
+            PartialTrie::Leaf { nibbles, value } => match key == *nibbles {
+                true => Ok(value_if_present(value)),
+                false => Ok(None),
+            },
+        }
+    }
+
+    /// Inserts `value` at `key`, performing the standard MPT mutation:
+    /// splitting extensions/leaves into branches (and new extensions) at the
+    /// point where the existing path and `key` diverge.
+    ///
+    /// Fails without touching `self` if the path to `key` descends into a
+    /// `Hash` node, since that subtree's data has been pruned and there's
+    /// nothing to insert into.
+    pub fn insert(&mut self, key: Nibbles, value: Vec<u8>) -> Result<(), TrieInsertError> {
+        Self::insert_in_place(self, key, value)
+    }
+
+    /// Inserts into `node` in place, only ever replacing the exact node
+    /// where the key path and the existing path meet or diverge, so an
+    /// `Err` leaves every node on the path untouched.
+    fn insert_in_place(
+        node: &mut PartialTrie,
+        key: Nibbles,
+        value: Vec<u8>,
+    ) -> Result<(), TrieInsertError> {
+        match node {
+            PartialTrie::Empty => {
+                *node = PartialTrie::Leaf {
+                    nibbles: key,
+                    value,
+                };
+                Ok(())
+            }
+            PartialTrie::Hash(_) => Err(TrieInsertError::HitHashNode(key)),
+            PartialTrie::Branch {
+                children,
+                value: branch_value,
+            } => match key.count {
+                0 => {
+                    *branch_value = value;
+                    Ok(())
+                }
+                _ => {
+                    let nib = key.get_nibble(0) as usize;
+                    let rest = key.mid(1);
+                    Self::insert_in_place(&mut children[nib], rest, value)
+                }
+            },
+            PartialTrie::Extension { nibbles, child } => match key.starts_with(nibbles) {
+                true => {
+                    let rest = key.mid(nibbles.count);
+                    Self::insert_in_place(child, rest, value)
+                }
+                false => {
+                    let common = key.common_prefix_len(nibbles);
+                    let existing_nibbles = *nibbles;
+                    let existing_child = Box::new(mem::take(&mut **child));
+
+                    *node = split_diverging_node(
+                        common,
+                        existing_nibbles,
+                        ExistingTail::ExtensionChild(existing_child),
+                        key,
+                        value,
+                    );
+
+                    Ok(())
+                }
+            },
+            PartialTrie::Leaf {
+                nibbles,
+                value: leaf_value,
+            } => match key == *nibbles {
+                true => {
+                    *leaf_value = value;
+                    Ok(())
+                }
+                false => {
+                    let common = key.common_prefix_len(nibbles);
+                    let existing_nibbles = *nibbles;
+                    let existing_value = mem::take(leaf_value);
+
+                    *node = split_diverging_node(
+                        common,
+                        existing_nibbles,
+                        ExistingTail::LeafValue(existing_value),
+                        key,
+                        value,
+                    );
+
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+/// Returns `value` as `Some` unless it's empty, our convention (shared with
+/// the RLP encoding in [`crate::trie_hashing`]) for "no value at this node".
+fn value_if_present(value: &[u8]) -> Option<&[u8]> {
+    (!value.is_empty()).then_some(value)
+}
+
+/// Splits an `Extension`/`Leaf` node whose path diverges from `key` at nibble
+/// `common` into a `Branch`, wrapped in a new, shorter `Extension` if any
+/// common prefix survives.
+fn split_diverging_node(
+    common: usize,
+    existing_nibbles: Nibbles,
+    existing_tail: ExistingTail,
+    key: Nibbles,
+    value: Vec<u8>,
+) -> PartialTrie {
+    let mut children: [Box<PartialTrie>; 16] =
+        std::array::from_fn(|_| Box::new(PartialTrie::Empty));
+    let mut branch_value = Vec::new();
+
+    if common < existing_nibbles.count {
+        let old_nib = existing_nibbles.get_nibble(common) as usize;
+        let old_rest = existing_nibbles.mid(common + 1);
+
+        children[old_nib] = Box::new(match existing_tail {
+            ExistingTail::LeafValue(v) => PartialTrie::Leaf {
+                nibbles: old_rest,
+                value: v,
+            },
+            ExistingTail::ExtensionChild(child) => match old_rest.is_empty() {
+                true => *child,
+                false => PartialTrie::Extension {
+                    nibbles: old_rest,
+                    child,
+                },
+            },
+        });
+    } else if let ExistingTail::LeafValue(v) = existing_tail {
+        branch_value = v;
+    }
+
+    if common < key.count {
+        let new_nib = key.get_nibble(common) as usize;
+        let new_rest = key.mid(common + 1);
+        children[new_nib] = Box::new(PartialTrie::Leaf {
+            nibbles: new_rest,
+            value,
+        });
+    } else {
+        branch_value = value;
+    }
+
+    let branch = PartialTrie::Branch {
+        children,
+        value: branch_value,
+    };
+
+    match common {
+        0 => branch,
+        _ => PartialTrie::Extension {
+            nibbles: existing_nibbles.split_at_idx_prefix(common),
+            child: Box::new(branch),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partial_trie::PartialTrie;
+    use crate::utils::nibbles;
+
+    #[test]
+    fn insert_then_get_round_trips_a_single_key() {
+        let mut trie = PartialTrie::Empty;
+        trie.insert(nibbles(0x1234), b"value".to_vec()).unwrap();
+
+        assert_eq!(trie.get(nibbles(0x1234)).unwrap(), Some(&b"value"[..]));
+    }
+
+    #[test]
+    fn get_on_missing_key_returns_none() {
+        let mut trie = PartialTrie::Empty;
+        trie.insert(nibbles(0x1234), b"value".to_vec()).unwrap();
+
+        assert_eq!(trie.get(nibbles(0x9)).unwrap(), None);
+        assert_eq!(trie.get(nibbles(0x1235)).unwrap(), None);
+    }
+
+    #[test]
+    fn repeated_insertion_reads_every_key_back() {
+        let entries = [
+            (nibbles(0x1234), b"a".to_vec()),
+            (nibbles(0x1256), b"b".to_vec()),
+            (nibbles(0x12), b"c".to_vec()),
+            (nibbles(0x9), b"d".to_vec()),
+            (nibbles(0x1234abcd), b"e".to_vec()),
+        ];
+
+        let mut trie = PartialTrie::Empty;
+        for (key, value) in &entries {
+            trie.insert(*key, value.clone()).unwrap();
+        }
+
+        for (key, value) in &entries {
+            assert_eq!(trie.get(*key).unwrap(), Some(&value[..]));
+        }
+    }
+
+    #[test]
+    fn get_on_hash_node_returns_distinct_error() {
+        let trie = PartialTrie::Hash(ethereum_types::H256::zero());
+
+        assert!(matches!(
+            trie.get(nibbles(0x1)),
+            Err(super::TrieGetError::HitHashNode(_))
+        ));
+    }
+
+    #[test]
+    fn insert_into_hash_node_returns_distinct_error_and_leaves_trie_untouched() {
+        let h = ethereum_types::H256::zero();
+        let mut trie = PartialTrie::Hash(h);
+
+        let err = trie.insert(nibbles(0x1), b"value".to_vec()).unwrap_err();
+
+        assert!(matches!(err, super::TrieInsertError::HitHashNode(_)));
+        assert_eq!(trie, PartialTrie::Hash(h));
+    }
+
+    #[test]
+    fn insert_into_branch_descending_into_hash_node_leaves_trie_untouched() {
+        let h = ethereum_types::H256::zero();
+
+        // A branch whose child at nibble `1` has been pruned into a `Hash`
+        // node.
+        let mut trie = PartialTrie::Branch {
+            children: std::array::from_fn(|i| {
+                Box::new(if i == 1 {
+                    PartialTrie::Hash(h)
+                } else {
+                    PartialTrie::Empty
+                })
+            }),
+            value: Vec::new(),
+        };
+
+        let err = trie.insert(nibbles(0x12), b"value".to_vec()).unwrap_err();
+        assert!(matches!(err, super::TrieInsertError::HitHashNode(_)));
+
+        // The other slots are untouched, and the pruned slot is still a
+        // `Hash` node rather than having been overwritten.
+        match &trie {
+            PartialTrie::Branch { children, value } => {
+                assert!(value.is_empty());
+                assert_eq!(*children[1], PartialTrie::Hash(h));
+                for (i, child) in children.iter().enumerate() {
+                    if i != 1 {
+                        assert_eq!(**child, PartialTrie::Empty);
+                    }
+                }
+            }
+            _ => panic!("expected trie to still be a `Branch`"),
+        }
+    }
+}